@@ -16,7 +16,7 @@ fi
 
 case ${1:-} in
   -I* | --install)
-    OPT="$(getopt -o Ipgs -l install -n "$(basename "$0")" -- "$@")" \
+    OPT="$(getopt -o Ibilpgs -l install -n "$(basename "$0")" -- "$@")" \
       || exit 1
     export OPT
     do_install
@@ -28,7 +28,7 @@ case ${1:-} in
     do_test
     ;;
   -U* | --uninstall)
-    OPT="$(getopt -o Ug -l uninstall -n "$(basename "$0")" -- "$@")" \
+    OPT="$(getopt -o Udgr -l uninstall -n "$(basename "$0")" -- "$@")" \
       || exit 1
     export OPT
     do_uninstall
@@ -9,6 +9,8 @@ set -o nounset
 # Perform a full install.
 do_install()
 {
+  check_container
+
   update_dotfiles
   install_git_submodules
   update_git_submodules
@@ -32,6 +34,9 @@ do_test()
   install_git_submodules
   update_git_submodules
 
+  test_chmod
+  test_duplicates
+  test_symlinks
   test_shellcheck
 }
 
@@ -40,9 +45,12 @@ do_test()
 # Perform a full uninstall.
 do_uninstall()
 {
+  check_container
+
   update_dotfiles
   install_git_submodules
   update_git_submodules
 
   uninstall_symlinks
+  uninstall_dangling_symlinks
 }
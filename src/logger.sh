@@ -33,14 +33,19 @@ log_usage()
 {
   echo "Usage:"
   echo "  $(basename "$0")"
-  echo "  $(basename "$0") {-I --install}   [-g] [-p] [-s]"
-  echo "  $(basename "$0") {-U --uninstall} [-g]"
+  echo "  $(basename "$0") {-I --install}   [-b] [-g] [-i] [-l] [-p] [-s]"
+  echo "  $(basename "$0") {-U --uninstall} [-d] [-g] [-r]"
   echo "  $(basename "$0") {-T --test}"
   echo "  $(basename "$0") {-h --help}"
   echo
   echo "Options:"
+  echo "  -b  Back up files replaced by symlinks"
+  echo "  -d  Remove dangling symlinks into dotfiles"
   echo "  -g  Configure GUI environment"
+  echo "  -i  Install system packages interactively"
+  echo "  -l  Create relative symlinks"
   echo "  -p  Install system packages"
+  echo "  -r  Restore backed up files"
   echo "  -s  Install systemd units"
   exit
 }
@@ -59,4 +64,15 @@ log_stage()
     _work=true
     echo ":: $1..."
   fi
+}
+
+# log_warn
+#
+# Log a warning message.
+#
+# Args:
+#     $1 - The message.
+log_warn()
+{
+  echo "WARNING: $1"
 }
\ No newline at end of file
@@ -5,9 +5,22 @@ set -o nounset
 . "$DIR"/src/logger.sh
 . "$DIR"/src/utils.sh
 
+# check_container
+#
+# Warn about options that are ignored inside a container.
+check_container()
+{(
+  if is_flag_set "g" \
+    && is_container
+  then
+    log_warn "Ignoring -g inside a container"
+  fi
+)}
+
 # configure_file_mode_bits
 #
-# Configure file mode bits.
+# Configure file mode bits. Entries are only applied recursively when they
+# include the -R flag, and missing paths are skipped with a warning.
 configure_file_mode_bits()
 {(
   for env in "$DIR"/env/*
@@ -17,7 +30,19 @@ configure_file_mode_bits()
     then
       while IFS='' read -r line || [ -n "$line" ]
       do
-        chmod -c -R "$(echo "$line" | cut -d" " -f1)" ~/."$(echo "$line" | cut -d" " -f2)"
+        if [ -e ~/."${line##* }" ]
+        then
+          case "$line" in
+            *" -R "*)
+              chmod -c -R "$(echo "$line" | cut -d" " -f1)" ~/."${line##* }"
+              ;;
+            *)
+              chmod -c "$(echo "$line" | cut -d" " -f1)" ~/."${line##* }"
+              ;;
+          esac
+        else
+          log_warn "Skipping mode bits for ~/.${line##* }, it does not exist"
+        fi
       done < "$env"/chmod.conf
     fi
   done
@@ -40,25 +65,35 @@ configure_fonts()
 
 # configure_shell
 #
-# Set the user shell.
+# Set the user shell. The shell must be listed in /etc/shells.
 configure_shell()
 {(
-  if is_program_installed "zsh" \
-    && [ "$SHELL" != "$(zsh -c "command -vp zsh")" ] \
-    && [ ! -f /.dockerenv ] \
-    && [ "$(passwd --status "$USER" | cut -d" " -f2)" = "P" ]
+  if is_program_installed "zsh"
   then
-    log_stage "Configuring user shell"
-    chsh -s "$(zsh -c "command -vp zsh")"
+    zsh="$(zsh -c "command -vp zsh")"
+    if [ "$SHELL" != "$zsh" ] \
+      && ! is_container \
+      && [ "$(passwd --status "$USER" | cut -d" " -f2)" = "P" ]
+    then
+      if grep -qsxF "$zsh" /etc/shells
+      then
+        log_stage "Configuring user shell"
+        chsh -s "$zsh"
+      else
+        log_warn "Skipping user shell, $zsh is not in /etc/shells"
+      fi
+    fi
   fi
 )}
 
 # configure_systemd
 #
-# Configure systemd.
+# Configure systemd. Units prefixed with ! are disabled and units prefixed
+# with !! are masked, otherwise they are enabled. Skipped inside containers.
 configure_systemd()
 {(
   if is_flag_set "s" \
+    && ! is_container \
     && [ "$(ps -p 1 -o comm=)" = "systemd" ] \
     && is_program_installed "systemctl"
   then
@@ -69,16 +104,34 @@ configure_systemd()
       then
         while IFS='' read -r unit || [ -n "$unit" ]
         do
-          if systemctl --user list-unit-files | cut -d" " -f1 | grep -qx "$unit" \
-            && ! systemctl --user is-enabled --quiet "$unit"
-          then
-            log_stage "Configuring systemd"
-            systemctl --user enable "$unit"
-            if [ "$(systemctl is-system-running)" = "running" ]
-            then
-              systemctl --user start "$unit"
-            fi
-          fi
+          case "$unit" in
+            "!!"*)
+              if [ "$(systemctl --user is-enabled "${unit#!!}")" != "masked" ]
+              then
+                log_stage "Configuring systemd"
+                systemctl --user mask --now "${unit#!!}"
+              fi
+              ;;
+            "!"*)
+              if [ "$(systemctl --user is-enabled "${unit#!}")" = "enabled" ]
+              then
+                log_stage "Configuring systemd"
+                systemctl --user disable --now "${unit#!}"
+              fi
+              ;;
+            *)
+              if systemctl --user list-unit-files | cut -d" " -f1 | grep -qx "$unit" \
+                && ! systemctl --user is-enabled --quiet "$unit"
+              then
+                log_stage "Configuring systemd"
+                systemctl --user enable "$unit"
+                if [ "$(systemctl is-system-running)" = "running" ]
+                then
+                  systemctl --user start "$unit"
+                fi
+              fi
+              ;;
+          esac
         done < "$env"/units.conf
       fi
     done
@@ -127,7 +180,11 @@ install_git_submodules()
 
 # install_packages
 #
-# Install packages.
+# Install packages. Entries already satisfied by an installed package,
+# including through provides, are skipped. Entries pacman can not resolve as
+# a package, group or provider are installed from the AUR with paru or yay.
+# Pacman and the AUR helper are run with --noconfirm unless interactive
+# installs are requested.
 install_packages()
 {(
   if is_flag_set "p" \
@@ -135,6 +192,9 @@ install_packages()
     && is_program_installed "pacman"
   then
     packages=""
+    aur_packages=""
+    entries=""
+    available="$(pacman -Slq; pacman -Sgq)"
     for env in "$DIR"/env/*
     do
       if ! is_env_ignored "$(basename "$env")" \
@@ -142,25 +202,66 @@ install_packages()
       then
         while IFS='' read -r package || [ -n "$package" ]
         do
-          if ! pacman -Qq "$package" >/dev/null 2>&1
-          then
-            packages="$packages $package"
-          fi
+          entries="$entries $package"
         done < "$env"/packages.conf
       fi
     done
+    # shellcheck disable=SC2086
+    for package in $(pacman -T $entries || true)
+    do
+      if echo "$available" | grep -qxF "$package" \
+        || pacman -Sp "$package" >/dev/null 2>&1
+      then
+        packages="$packages $package"
+      else
+        aur_packages="$aur_packages $package"
+      fi
+    done
     if [ -n "$packages" ]
     then
       log_stage "Installing packages"
-      # shellcheck disable=SC2086
-      sudo pacman -S --quiet --needed $packages
+      if is_flag_set "i"
+      then
+        # shellcheck disable=SC2086
+        sudo pacman -S --quiet --needed $packages
+      else
+        # shellcheck disable=SC2086
+        sudo pacman -S --quiet --needed --noconfirm $packages
+      fi
+    fi
+    if [ -n "$aur_packages" ]
+    then
+      helper=""
+      for program in paru yay
+      do
+        if [ -z "$helper" ] \
+          && is_program_installed "$program"
+        then
+          helper="$program"
+        fi
+      done
+      if [ -z "$helper" ]
+      then
+        log_warn "Skipping AUR packages$aur_packages, install paru or yay to install them"
+      else
+        log_stage "Installing packages"
+        if is_flag_set "i"
+        then
+          # shellcheck disable=SC2086
+          "$helper" -S --needed $aur_packages
+        else
+          # shellcheck disable=SC2086
+          "$helper" -S --needed --noconfirm $aur_packages
+        fi
+      fi
     fi
   fi
 )}
 
 # install_symlinks
 #
-# Install symlinks.
+# Install symlinks. Existing files are moved aside to a timestamped backup
+# when backups are requested, and links are made relative when requested.
 install_symlinks()
 {(
   for env in "$DIR"/env/*
@@ -170,17 +271,40 @@ install_symlinks()
     then
       while IFS='' read -r symlink || [ -n "$symlink" ]
       do
-        if ! is_symlink_installed "$(basename "$env")" "$symlink"
+        source="$(get_symlink_source "$symlink")"
+        destination="$(get_symlink_destination "$symlink")"
+        broken="$(get_broken_parent ~/."$destination")"
+        if is_symlink_circular "$(basename "$env")" "$symlink"
+        then
+          log_warn "Skipping circular symlink $symlink"
+        elif [ -n "$broken" ]
+        then
+          log_warn "Skipping symlink $symlink, $broken is a broken symlink"
+        elif is_path_in_dotfiles ~/."$destination"
+        then
+          log_warn "Skipping symlink $symlink, destination is inside $DIR"
+        elif ! is_symlink_installed "$(basename "$env")" "$symlink"
         then
           log_stage "Installing symlinks"
-          case "$symlink" in
-            *"/"*) mkdir -pv ~/."$(echo "$symlink" | rev | cut -d/ -f2- | rev)"
+          case "$destination" in
+            *"/"*) mkdir -pv ~/."$(echo "$destination" | rev | cut -d/ -f2- | rev)"
           esac
-          if [ -e ~/."$symlink" ]
+          if [ -e ~/."$destination" ]
           then
-            rm -rvf ~/."$symlink"
+            if is_flag_set "b" \
+              && [ ! -L ~/."$destination" ]
+            then
+              mv -v ~/."$destination" ~/."$destination".dotfiles-backup-"$(date +%Y%m%d%H%M%S)"
+            else
+              rm -rvf ~/."$destination"
+            fi
+          fi
+          if is_flag_set "l"
+          then
+            ln -snvfr "$env"/symlinks/"$source" ~/."$destination"
+          else
+            ln -snvf "$env"/symlinks/"$source" ~/."$destination"
           fi
-          ln -snvf "$env"/symlinks/"$symlink" ~/."$symlink"
         fi
       done < "$env"/symlinks.conf
     fi
@@ -189,7 +313,7 @@ install_symlinks()
 
 # install_vscode_extensions
 #
-# Install vscode extensions.
+# Install vscode extensions. Extension IDs are case-insensitive.
 install_vscode_extensions()
 {(
   for code in code code-insiders
@@ -200,7 +324,7 @@ install_vscode_extensions()
       extensions=$($code --list-extensions)
       while IFS='' read -r extension || [ -n "$extension" ]
       do
-        if ! echo "$extensions" | grep -qw "$extension"
+        if ! echo "$extensions" | grep -qixF "$extension"
         then
           log_stage "Installing $code extensions"
           $code --install-extension "$extension"
@@ -210,6 +334,106 @@ install_vscode_extensions()
   done
 )}
 
+# test_chmod
+#
+# Warn about invalid modes and paths configured with different modes.
+test_chmod()
+{(
+  log_stage "Validating file mode bits"
+  tmpfile="$(mktemp)"
+  for env in "$DIR"/env/*
+  do
+    if [ -e "$env"/chmod.conf ]
+    then
+      n=0
+      while IFS='' read -r line || [ -n "$line" ]
+      do
+        n=$((n + 1))
+        case "$(echo "$line" | cut -d" " -f1)" in
+          [0-7][0-7][0-7] | [0-7][0-7][0-7][0-7])
+            ;;
+          *)
+            log_warn "Invalid mode in env/$(basename "$env")/chmod.conf line $n: $line"
+            ;;
+        esac
+        echo "${line##* } $(echo "$line" | cut -d" " -f1)" >> "$tmpfile"
+      done < "$env"/chmod.conf
+    fi
+  done
+  sort -u "$tmpfile" | cut -d" " -f1 | uniq -d | while IFS='' read -r path
+  do
+    modes=""
+    applied=""
+    while IFS=' ' read -r name mode
+    do
+      if [ "$name" = "$path" ]
+      then
+        applied="$mode"
+        case "$modes " in
+          *" $mode "*)
+            ;;
+          *)
+            modes="$modes $mode"
+            ;;
+        esac
+      fi
+    done < "$tmpfile"
+    log_warn "Conflicting modes$modes for $path, $applied is applied"
+  done
+  rm "$tmpfile"
+)}
+
+# test_duplicates
+#
+# Warn about entries configured more than once.
+test_duplicates()
+{(
+  log_stage "Checking for duplicate entries"
+  for conf in chmod packages symlinks
+  do
+    tmpfile="$(mktemp)"
+    for env in "$DIR"/env/*
+    do
+      if [ -e "$env"/"$conf".conf ]
+      then
+        while IFS='' read -r line || [ -n "$line" ]
+        do
+          case "$conf" in
+            chmod)
+              echo "$(basename "$env") ${line##* }"
+              ;;
+            symlinks)
+              echo "$(basename "$env") $(get_symlink_destination "$line")"
+              ;;
+            *)
+              echo "$(basename "$env") $line"
+              ;;
+          esac
+        done < "$env"/"$conf".conf
+      fi
+    done > "$tmpfile"
+    cut -d" " -f2- "$tmpfile" | sort | uniq -d | while IFS='' read -r entry
+    do
+      envs=""
+      while IFS=' ' read -r name value
+      do
+        if [ "$value" = "$entry" ]
+        then
+          case "$envs " in
+            *" $name "*)
+              ;;
+            *)
+              envs="$envs $name"
+              ;;
+          esac
+        fi
+      done < "$tmpfile"
+      log_warn "Duplicate $conf entry $entry in$envs"
+    done
+    rm "$tmpfile"
+  done
+)}
+
 # test_shellcheck
 #
 # Run shellcheck.
@@ -227,10 +451,11 @@ test_shellcheck()
       then
         while IFS='' read -r symlink || [ -n "$symlink" ]
         do
-          if [ -d "$env"/symlinks/"$symlink" ]
+          source="$(get_symlink_source "$symlink")"
+          if [ -d "$env"/symlinks/"$source" ]
           then
             tmpfile="$(mktemp)"
-            find "$env"/symlinks/"$symlink" -type f > "$tmpfile"
+            find "$env"/symlinks/"$source" -type f > "$tmpfile"
             while IFS='' read -r line || [ -n "$line" ]
             do
               ignore=false
@@ -252,9 +477,9 @@ test_shellcheck()
               fi
             done < "$tmpfile"
             rm "$tmpfile"
-          elif is_shell_script "$env"/symlinks/"$symlink"
+          elif is_shell_script "$env"/symlinks/"$source"
           then
-            scripts="$scripts $env"/symlinks/"$symlink"
+            scripts="$scripts $env"/symlinks/"$source"
           fi
         done < "$env"/symlinks.conf
       fi
@@ -264,9 +489,80 @@ test_shellcheck()
   fi
 )}
 
+# test_symlinks
+#
+# Warn about symlinks whose source does not exist.
+test_symlinks()
+{(
+  log_stage "Validating symlinks"
+  for env in "$DIR"/env/*
+  do
+    if [ -e "$env"/symlinks.conf ]
+    then
+      while IFS='' read -r symlink || [ -n "$symlink" ]
+      do
+        source="$(get_symlink_source "$symlink")"
+        if [ ! -e "$env"/symlinks/"$source" ]
+        then
+          log_warn "Missing symlink source $env/symlinks/$source"
+        fi
+      done < "$env"/symlinks.conf
+    fi
+  done
+)}
+
+# uninstall_dangling_symlinks
+#
+# Uninstall symlinks into the dotfiles whose source no longer exists. Only
+# the home directory and the parent directories of configured symlinks are
+# searched.
+uninstall_dangling_symlinks()
+{(
+  if is_flag_set "d"
+  then
+    dirs="$HOME"
+    for env in "$DIR"/env/*
+    do
+      if [ -e "$env"/symlinks.conf ]
+      then
+        while IFS='' read -r symlink || [ -n "$symlink" ]
+        do
+          destination="$(get_symlink_destination "$symlink")"
+          case "$destination" in
+            *"/"*) dirs="$dirs
+$HOME/.$(echo "$destination" | rev | cut -d/ -f2- | rev)"
+          esac
+        done < "$env"/symlinks.conf
+      fi
+    done
+    tmpfile="$(mktemp)"
+    echo "$dirs" | sort -u | while IFS='' read -r dir
+    do
+      if [ -d "$dir" ]
+      then
+        find "$dir" -maxdepth 1 -type l
+      fi
+    done > "$tmpfile"
+    while IFS='' read -r link || [ -n "$link" ]
+    do
+      case "$(readlink -m "$link")" in
+        "$DIR"/env/*/symlinks/*)
+          if [ ! -e "$link" ]
+          then
+            log_stage "Uninstalling dangling symlinks"
+            rm -vf "$link"
+          fi
+          ;;
+      esac
+    done < "$tmpfile"
+    rm "$tmpfile"
+  fi
+)}
+
 # uninstall_symlinks
 #
-# Uninstall symlinks.
+# Uninstall symlinks. The newest backup of each file is restored when
+# requested.
 uninstall_symlinks()
 {(
   for env in "$DIR"/env/*
@@ -276,10 +572,19 @@ uninstall_symlinks()
     then
       while IFS='' read -r symlink || [ -n "$symlink" ]
       do
-        if is_symlink_installed "$env" "$symlink"
+        destination="$(get_symlink_destination "$symlink")"
+        if is_symlink_installed "$(basename "$env")" "$symlink"
         then
           log_stage "Uninstalling symlinks"
-          rm -vf ~/."$symlink"
+          rm -vf ~/."$destination"
+          if is_flag_set "r"
+          then
+            backup="$(find "$(dirname ~/."$destination")" -maxdepth 1 -name "$(basename ~/."$destination").dotfiles-backup-*" | sort | tail -n 1)"
+            if [ -n "$backup" ]
+            then
+              mv -v "$backup" ~/."$destination"
+            fi
+          fi
         fi
       done < "$env"/symlinks.conf
     fi
@@ -2,6 +2,80 @@
 set -o errexit
 set -o nounset
 
+# get_broken_parent
+#
+# Get the first parent directory of a path that is a broken symlink.
+#
+# Args:
+#     $1 - The path to check.
+#
+# return:
+#     string - The broken parent, or nothing if all parents resolve.
+get_broken_parent()
+{
+  parent="$(dirname "$1")"
+  while [ "$parent" != "/" ] \
+    && [ "$parent" != "." ]
+  do
+    if [ -L "$parent" ] \
+      && [ ! -e "$parent" ]
+    then
+      echo "$parent"
+      return
+    fi
+    parent="$(dirname "$parent")"
+  done
+}
+
+# get_symlink_destination
+#
+# Get the destination of a symlink entry. Entries are either a path or a
+# "source => destination" pair.
+#
+# Args:
+#     $1 - The symlink entry.
+#
+# return:
+#     string - The destination relative to the home directory, without the
+#              leading dot.
+get_symlink_destination()
+{
+  echo "$1" | sed "s/.* => //"
+}
+
+# get_symlink_source
+#
+# Get the source of a symlink entry. Entries are either a path or a
+# "source => destination" pair.
+#
+# Args:
+#     $1 - The symlink entry.
+#
+# return:
+#     string - The source relative to the symlinks directory.
+get_symlink_source()
+{
+  echo "$1" | sed "s/ => .*//"
+}
+
+# is_container
+#
+# Check if running inside a container. Detection is best effort.
+#
+# return:
+#     bool - True if running inside a container.
+is_container()
+{
+  if [ -f /.dockerenv ] \
+    || [ -n "${container:-}" ] \
+    || grep -qsE "docker|kubepods|lxc" /proc/1/cgroup
+  then
+    return 0
+  else
+    return 1
+  fi
+}
+
 # is_env_ignored
 #
 # Check if an environment is ignored.
@@ -28,7 +102,8 @@ is_env_ignored()
       fi
       ;;
     base-gui)
-      if ! is_flag_set "g"
+      if ! is_flag_set "g" \
+        || is_container
       then
         return 0
       fi
@@ -58,6 +133,25 @@ is_flag_set()
   esac
 }
 
+# is_path_in_dotfiles
+#
+# Check if a path is inside the dotfiles directory.
+#
+# Args:
+#     $1 - The path to check.
+#
+# return:
+#     bool - True if the path is inside the dotfiles directory.
+is_path_in_dotfiles()
+{
+  case "$(readlink -m "$(dirname "$1")")"/ in
+    "$DIR"/*)
+      return 0
+      ;;
+  esac
+  return 1
+}
+
 # is_program_installed
 #
 # Check if a program is installed.
@@ -99,6 +193,33 @@ is_shell_script()
   return 1
 }
 
+# is_symlink_circular
+#
+# Check if installing a symlink would create a cycle.
+#
+# Args:
+#     $1 - The environment to be checked.
+#     $2 - The symlink to be checked.
+#
+# return:
+#     bool - True if an existing symlink source can not be resolved or
+#            resolves to the symlink itself.
+is_symlink_circular()
+{
+  link_source="$DIR"/env/"$1"/symlinks/"$(get_symlink_source "$2")"
+  link_destination="$HOME"/."$(get_symlink_destination "$2")"
+  if [ -e "$link_source" ] \
+    || [ -L "$link_source" ]
+  then
+    if ! target="$(readlink -f "$link_source")" \
+      || [ "$target" = "$(readlink -m "$(dirname "$link_destination")")/$(basename "$link_destination")" ]
+    then
+      return 0
+    fi
+  fi
+  return 1
+}
+
 # is_symlink_installed
 #
 # Check if a symlink is installed.
@@ -111,7 +232,7 @@ is_shell_script()
 #     bool - True if the symlink is installed.
 is_symlink_installed()
 {
-  if [ "$(readlink -f "$DIR"/env/"$1"/symlinks/"$2")" = "$(readlink -f ~/."$2")" ]
+  if [ "$(readlink -f "$DIR"/env/"$1"/symlinks/"$(get_symlink_source "$2")")" = "$(readlink -f ~/."$(get_symlink_destination "$2")")" ]
   then
     return 0
   else